@@ -1,5 +1,6 @@
 use anyhow::Result;
 use serde_derive::{Serialize, Deserialize};
+use sodiumoxide::crypto::{generichash, pwhash, secretbox, sign};
 use std::io::prelude::*;
 
 type SecretKey = sodiumoxide::crypto::box_::SecretKey;
@@ -38,18 +39,128 @@ fn from_id(id: &String) -> Result<PublicKey> {
     Ok(public_key)
 }
 
+// Untagged so a plain `key = "..."` identity written by an older version of
+// this crate still deserializes as `Plain`, without needing a migration step.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum IdentityKey {
+    Plain {
+        key: String,
+    },
+    Protected {
+        public_key: String,
+        salt: String,
+        opslimit: usize,
+        memlimit: usize,
+        nonce: String,
+        ciphertext: String,
+    },
+}
+
+fn derive_symmetric_key(passphrase: &str, salt: &pwhash::Salt, opslimit: pwhash::OpsLimit, memlimit: pwhash::MemLimit) -> Result<secretbox::Key> {
+    let mut key_bytes = [0u8; secretbox::KEYBYTES];
+    pwhash::derive_key(&mut key_bytes, passphrase.as_bytes(), salt, opslimit, memlimit)
+        .map_err(|_| anyhow!("Could not derive a key from that passphrase."))?;
+    Ok(secretbox::Key::from_slice(&key_bytes).unwrap())
+}
+
+fn protect_secret_key(passphrase: &str, raw_secret: &[u8], raw_public: &[u8]) -> Result<IdentityKey> {
+    let salt = pwhash::gen_salt();
+    let opslimit = pwhash::OPSLIMIT_INTERACTIVE;
+    let memlimit = pwhash::MEMLIMIT_INTERACTIVE;
+    let symmetric_key = derive_symmetric_key(passphrase, &salt, opslimit, memlimit)?;
+    let nonce = secretbox::gen_nonce();
+    let ciphertext = secretbox::seal(raw_secret, &nonce, &symmetric_key);
+    Ok(IdentityKey::Protected {
+        public_key: base64::encode(raw_public),
+        salt: base64::encode(&salt[..]),
+        opslimit: opslimit.0,
+        memlimit: memlimit.0,
+        nonce: base64::encode(&nonce[..]),
+        ciphertext: base64::encode(&ciphertext),
+    })
+}
+
+fn unprotect_secret_key(passphrase: &str, salt: &str, opslimit: usize, memlimit: usize, nonce: &str, ciphertext: &str) -> Result<Vec<u8>> {
+    let salt = base64::decode(salt).map_err(|_| anyhow!("Corrupt identity."))?;
+    let salt = pwhash::Salt::from_slice(&salt).ok_or_else(|| anyhow!("Corrupt identity."))?;
+    let nonce = base64::decode(nonce).map_err(|_| anyhow!("Corrupt identity."))?;
+    let nonce = secretbox::Nonce::from_slice(&nonce).ok_or_else(|| anyhow!("Corrupt identity."))?;
+    let ciphertext = base64::decode(ciphertext).map_err(|_| anyhow!("Corrupt identity."))?;
+    let symmetric_key = derive_symmetric_key(passphrase, &salt, pwhash::OpsLimit(opslimit), pwhash::MemLimit(memlimit))?;
+    secretbox::open(&ciphertext, &nonce, &symmetric_key).map_err(|_| anyhow!("Incorrect passphrase."))
+}
+
+// Brain-wallet style derivation: the same phrase always derives down to the
+// same Curve25519 seed, so the keypair (and thus the public id) it produces
+// is reproducible on any machine without ever touching ~/.gqg.toml. This goes
+// through Argon2 (`pwhash`), same as passphrase-protected identities, rather
+// than a single fast hash: a phrase (unlike a random secret key) is often
+// guessable, and a raw BLAKE2b pass would let an attacker test dictionary
+// candidates against a known public id at gigabytes-per-second. The salt is
+// fixed and domain-separated rather than random, since it must reproduce the
+// same seed for the same phrase on every machine; it doesn't need to be
+// secret, only distinct from other `derive_key` call sites in this module.
+fn derive_seed(phrase: &str) -> Result<sodiumoxide::crypto::box_::Seed> {
+    let mut salt_state = generichash::State::new(Some(pwhash::SALTBYTES), None)
+        .map_err(|_| anyhow!("Could not derive a seed from that phrase."))?;
+    salt_state.update(b"gqg-lib recovery phrase seed salt v1")
+        .map_err(|_| anyhow!("Could not derive a seed from that phrase."))?;
+    let salt_digest = salt_state.finalize().map_err(|_| anyhow!("Could not derive a seed from that phrase."))?;
+    let salt = pwhash::Salt::from_slice(salt_digest.as_ref())
+        .ok_or_else(|| anyhow!("Could not derive a seed from that phrase."))?;
+
+    let mut seed_bytes = [0u8; sodiumoxide::crypto::box_::SEEDBYTES];
+    pwhash::derive_key(&mut seed_bytes, phrase.as_bytes(), &salt, pwhash::OPSLIMIT_INTERACTIVE, pwhash::MEMLIMIT_INTERACTIVE)
+        .map_err(|_| anyhow!("Could not derive a seed from that phrase."))?;
+    sodiumoxide::crypto::box_::Seed::from_slice(&seed_bytes).ok_or_else(|| anyhow!("Could not derive a seed from that phrase."))
+}
+
+/// Derives the public identifier a recovery phrase would produce, without
+/// importing it, so a user can confirm it matches before trusting the phrase.
+pub fn derive_public_id(phrase: &str) -> Result<String> {
+    let seed = derive_seed(phrase)?;
+    let (public_key, _) = sodiumoxide::crypto::box_::keypair_from_seed(&seed);
+    Ok(to_id(&public_key))
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Identity {
     pub name: String,
-    pub key: String,
+    #[serde(flatten)]
+    pub key: IdentityKey,
 }
 
 impl Identity {
-    pub fn get_private_key(&self) -> SecretKey {
-        SecretKey::from_slice(&base64::decode(&self.key).unwrap()).unwrap()
+    pub fn get_private_key(&self, passphrase: Option<&str>) -> Result<SecretKey> {
+        let raw = match &self.key {
+            IdentityKey::Plain { key } => {
+                base64::decode(key).map_err(|_| anyhow!("Corrupt identity key."))?
+            }
+            IdentityKey::Protected { salt, opslimit, memlimit, nonce, ciphertext, .. } => {
+                let passphrase = passphrase.ok_or_else(|| anyhow!("This identity is passphrase-protected."))?;
+                unprotect_secret_key(passphrase, salt, *opslimit, *memlimit, nonce, ciphertext)?
+            }
+        };
+        SecretKey::from_slice(&raw).ok_or_else(|| anyhow!("Corrupt identity key."))
+    }
+
+    pub fn get_public_id(&self) -> Result<String> {
+        let raw = match &self.key {
+            IdentityKey::Plain { key } => {
+                let raw = base64::decode(key).map_err(|_| anyhow!("Corrupt identity key."))?;
+                SecretKey::from_slice(&raw).ok_or_else(|| anyhow!("Corrupt identity key."))?.public_key()
+            }
+            IdentityKey::Protected { public_key, .. } => {
+                let raw = base64::decode(public_key).map_err(|_| anyhow!("Corrupt identity key."))?;
+                PublicKey::from_slice(&raw).ok_or_else(|| anyhow!("Corrupt identity key."))?
+            }
+        };
+        Ok(to_id(&raw))
     }
-    pub fn get_public_id(&self) -> String {
-        to_id(&self.get_private_key().public_key())
+
+    pub fn is_protected(&self) -> bool {
+        matches!(self.key, IdentityKey::Protected { .. })
     }
 }
 
@@ -57,6 +168,14 @@ impl Identity {
 pub struct Friend {
     pub name: String,
     pub key: String,
+    // Base64-encoded Ed25519 public key this friend signs with, so that
+    // `verify()` can confirm a signature actually belongs to the box identity
+    // it claims rather than trusting whatever `sign_pk` an attacker embedded
+    // in the blob. Defaulted to empty so friends added before this field
+    // existed still deserialize; such a friend simply can't be authenticated
+    // until their signing key is learned and recorded.
+    #[serde(default)]
+    pub sign_pk: String,
 }
 
 impl<'a> Friend {
@@ -67,6 +186,11 @@ impl<'a> Friend {
     pub fn get_public_id(&'a self) -> &'a str {
         &self.key
     }
+
+    pub fn get_signing_public_key(&self) -> Option<sign::PublicKey> {
+        let raw = base64::decode(&self.sign_pk).ok()?;
+        sign::PublicKey::from_slice(&raw)
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -105,6 +229,18 @@ impl<'a> Database {
         dir.to_str().unwrap().to_string()
     }
 
+    pub fn backup_path() -> String {
+        let mut dir = dirs::home_dir().unwrap();
+        dir.push(".gqg.toml.bak");
+        dir.to_str().unwrap().to_string()
+    }
+
+    fn tmp_config_path() -> String {
+        let mut dir = dirs::home_dir().unwrap();
+        dir.push(".gqg.toml.tmp");
+        dir.to_str().unwrap().to_string()
+    }
+
     pub fn message_path_buf() -> std::path::PathBuf {
         let mut dir = dirs::home_dir().unwrap();
         dir.push(".gqg/messages/");
@@ -123,7 +259,16 @@ impl<'a> Database {
         let db_file: DatabaseFile;
         match std::fs::read_to_string(Self::config_path()) {
             Ok(config_file) => {
-                db_file = toml::from_str(&config_file).expect("Parsing error in the configuration file.");
+                match toml::from_str(&config_file) {
+                    Ok(parsed) => db_file = parsed,
+                    Err(_) => {
+                        eprintln!("Parsing error in the configuration file, falling back to backup...");
+                        let backup_file = std::fs::read_to_string(Self::backup_path())
+                            .expect("Configuration file is corrupt and no backup is available.");
+                        db_file = toml::from_str(&backup_file)
+                            .expect("Parsing error in the backup configuration file.");
+                    }
+                }
             }
             Err(err) => {
                 if err.kind() == std::io::ErrorKind::NotFound {
@@ -184,13 +329,80 @@ impl<'a> Database {
         None
     }
 
+    fn find_identity_mut(&'a mut self, name: &str) -> Option<&'a mut Identity> {
+        for id in &mut self.file.identity {
+            if id.name == name {
+                return Some(id);
+            }
+        }
+        None
+    }
+
     pub fn add_identity(&mut self, name: String) -> Result<()> {
         Self::validate_name(&name)?;
         if self.find_identity(&name).is_some() {
             return Err(anyhow!("Identity with that name already exists."));
         }
         let (_, key) = sodiumoxide::crypto::box_::gen_keypair();
-        self.file.identity.push(Identity { name, key: base64::encode(key) });
+        self.file.identity.push(Identity { name, key: IdentityKey::Plain { key: base64::encode(key) } });
+        self.dirty = true;
+        self.save();
+        Ok(())
+    }
+
+    pub fn add_identity_from_phrase(&mut self, name: String, phrase: &str) -> Result<()> {
+        Self::validate_name(&name)?;
+        if self.find_identity(&name).is_some() {
+            return Err(anyhow!("Identity with that name already exists."));
+        }
+        let seed = derive_seed(phrase)?;
+        let (_, key) = sodiumoxide::crypto::box_::keypair_from_seed(&seed);
+        self.file.identity.push(Identity { name, key: IdentityKey::Plain { key: base64::encode(key) } });
+        self.dirty = true;
+        self.save();
+        Ok(())
+    }
+
+    pub fn add_identity_with_passphrase(&mut self, name: String, passphrase: &str) -> Result<()> {
+        Self::validate_name(&name)?;
+        if self.find_identity(&name).is_some() {
+            return Err(anyhow!("Identity with that name already exists."));
+        }
+        let (public_key, secret_key) = sodiumoxide::crypto::box_::gen_keypair();
+        let key = protect_secret_key(passphrase, &secret_key[..], &public_key[..])?;
+        self.file.identity.push(Identity { name, key });
+        self.dirty = true;
+        self.save();
+        Ok(())
+    }
+
+    pub fn set_identity_passphrase(&mut self, name: &str, passphrase: &str) -> Result<()> {
+        let identity = self.find_identity_mut(name).ok_or_else(|| anyhow!("No such identity."))?;
+        if identity.is_protected() {
+            return Err(anyhow!("Identity is already passphrase-protected."));
+        }
+        let secret_key = identity.get_private_key(None)?;
+        let public_key = secret_key.public_key();
+        identity.key = protect_secret_key(passphrase, &secret_key[..], &public_key[..])?;
+        self.dirty = true;
+        self.save();
+        Ok(())
+    }
+
+    pub fn change_identity_passphrase(&mut self, name: &str, old_passphrase: Option<&str>, new_passphrase: &str) -> Result<()> {
+        let identity = self.find_identity_mut(name).ok_or_else(|| anyhow!("No such identity."))?;
+        let secret_key = identity.get_private_key(old_passphrase)?;
+        let public_key = secret_key.public_key();
+        identity.key = protect_secret_key(new_passphrase, &secret_key[..], &public_key[..])?;
+        self.dirty = true;
+        self.save();
+        Ok(())
+    }
+
+    pub fn remove_identity_passphrase(&mut self, name: &str, passphrase: &str) -> Result<()> {
+        let identity = self.find_identity_mut(name).ok_or_else(|| anyhow!("No such identity."))?;
+        let secret_key = identity.get_private_key(Some(passphrase))?;
+        identity.key = IdentityKey::Plain { key: base64::encode(&secret_key[..]) };
         self.dirty = true;
         self.save();
         Ok(())
@@ -218,18 +430,30 @@ impl<'a> Database {
         None
     }
 
-    pub fn add_friend(&mut self, name: String, key: String) -> Result<()> {
+    pub fn add_friend(&mut self, name: String, key: String, sign_pk: String) -> Result<()> {
         Self::validate_name(&name)?;
         if self.find_friend(&name).is_some() {
             return Err(anyhow!("Friend with that name already exists."));
         }
         Self::validate_id(&key)?;
-        self.file.friend.push(Friend { name, key });
+        let raw_sign_pk = base64::decode(&sign_pk).map_err(|_| anyhow!("Invalid signing key format."))?;
+        sign::PublicKey::from_slice(&raw_sign_pk).ok_or_else(|| anyhow!("Invalid signing key format."))?;
+        self.file.friend.push(Friend { name, key, sign_pk });
         self.dirty = true;
         self.save();
         Ok(())
     }
 
+    // In-memory only, no disk I/O: lets other modules' tests exercise
+    // friend-lookup behavior without touching the real `~/.gqg.toml`.
+    #[cfg(test)]
+    pub fn new_in_memory(friends: Vec<Friend>) -> Self {
+        Self {
+            file: DatabaseFile { misc: Misc { active_identity: "default".to_string() }, identity: Vec::new(), friend: friends },
+            dirty: false,
+        }
+    }
+
     pub fn del_friend(&mut self, name: String) -> Result<()> {
         for (n, friend) in self.get_friends().iter().enumerate() {
             if friend.name == name {
@@ -244,11 +468,27 @@ impl<'a> Database {
 
     fn save(&mut self) {
         if self.dirty {
-            // TODO: make backup, so that the config file is not accidentally wiped.
+            // Write-temp-then-rename so a crash or panic mid-write can never
+            // leave the config file truncated, and keep a rolling backup of
+            // the last good version so a corrupt write (or disk) is still
+            // recoverable.
             let toml = toml::to_string(&self.file).unwrap();
-            let mut f = std::fs::File::create(Database::config_path()).expect("Could not write config file.");
+            let tmp_path = Database::tmp_config_path();
+            let mut f = std::fs::File::create(&tmp_path).expect("Could not write config file.");
             f.write_all(toml.as_bytes()).expect("Could not write config file.");
             f.sync_all().expect("Could not write config file.");
+            drop(f);
+            // Only promote the current on-disk file to `.bak` if it still
+            // parses. Otherwise the main file is corrupt (and `load()` has
+            // already recovered in memory from the existing backup) so
+            // rotating it in would overwrite the one good backup with
+            // garbage instead of keeping it.
+            if let Ok(previous) = std::fs::read_to_string(Database::config_path()) {
+                if toml::from_str::<DatabaseFile>(&previous).is_ok() {
+                    std::fs::write(Database::backup_path(), previous).expect("Could not write backup config file.");
+                }
+            }
+            std::fs::rename(&tmp_path, Database::config_path()).expect("Could not write config file.");
         }
         self.dirty = false;
     }
@@ -259,3 +499,53 @@ impl std::ops::Drop for Database {
         self.save();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Exercises the crypto helpers directly rather than through `Database`
+    // methods, since those call `save()` and would write to the real
+    // `~/.gqg.toml` as a side effect.
+    #[test]
+    fn test_protect_unprotect_secret_key_roundtrip() {
+        let (public_key, secret_key) = sodiumoxide::crypto::box_::gen_keypair();
+        let protected = protect_secret_key("correct horse battery staple", &secret_key[..], &public_key[..]).unwrap();
+        match protected {
+            IdentityKey::Protected { salt, opslimit, memlimit, nonce, ciphertext, public_key: stored_public_key } => {
+                let recovered = unprotect_secret_key("correct horse battery staple", &salt, opslimit, memlimit, &nonce, &ciphertext).unwrap();
+                assert_eq!(recovered, secret_key[..].to_vec());
+                assert_eq!(stored_public_key, base64::encode(&public_key));
+            }
+            IdentityKey::Plain { .. } => panic!("expected a Protected identity key"),
+        }
+    }
+
+    #[test]
+    fn test_unprotect_secret_key_wrong_passphrase_fails() {
+        let (public_key, secret_key) = sodiumoxide::crypto::box_::gen_keypair();
+        let protected = protect_secret_key("correct horse battery staple", &secret_key[..], &public_key[..]).unwrap();
+        match protected {
+            IdentityKey::Protected { salt, opslimit, memlimit, nonce, ciphertext, .. } => {
+                assert!(unprotect_secret_key("wrong passphrase", &salt, opslimit, memlimit, &nonce, &ciphertext).is_err());
+            }
+            IdentityKey::Plain { .. } => panic!("expected a Protected identity key"),
+        }
+    }
+
+    #[test]
+    fn test_derive_public_id_is_deterministic_and_phrase_specific() {
+        let a = derive_public_id("correct horse battery staple").unwrap();
+        let b = derive_public_id("correct horse battery staple").unwrap();
+        let c = derive_public_id("a different recovery phrase").unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_derive_public_id_matches_derived_keypair() {
+        let seed = derive_seed("correct horse battery staple").unwrap();
+        let (public_key, _) = sodiumoxide::crypto::box_::keypair_from_seed(&seed);
+        assert_eq!(derive_public_id("correct horse battery staple").unwrap(), to_id(&public_key));
+    }
+}
@@ -1,6 +1,7 @@
 use base64;
 use lz4_compress;
 use sodiumoxide::crypto;
+use std::io::{BufRead, Read, Write};
 
 pub mod database;
 
@@ -13,8 +14,28 @@ type Nonce = sodiumoxide::crypto::box_::Nonce;
 
 const HEADER_MESSAGE: &str = "[GQG1-MESSAGE";
 const HEADER_FILE: &str = "[GQG1-FILE";
+const HEADER_FILE2: &str = "[GQG1-FILE2";
+const HEADER_SIG: &str = "[GQG1-SIG";
 const FOOTER: &str = "]";
 
+// RFC 4880-style armor: wrap the base64 body at this width and append a
+// CRC-24 checksum line so mangled copy/paste is caught before decryption.
+const ARMOR_LINE_WIDTH: usize = 64;
+const CRC24_INIT: u32 = 0xB704CE;
+const CRC24_POLY: u32 = 0x1864CFB;
+
+// Plaintext is pushed through secretstream in fixed-size chunks so memory
+// use stays bounded regardless of file size.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+// A legitimate `GQG1-FILE2` header is a few hundred bytes at most (two
+// packed keys, a nonce, a file name and some flags, all armored). Capping
+// how much of it `decode_stream` will buffer before giving up keeps a
+// header line with no closing `]` from forcing the whole, possibly
+// multi-gigabyte, input into memory before `decode_armor` gets a chance to
+// reject it.
+const MAX_STREAM_HEADER_LEN: usize = 8 * 1024;
+
 pub enum Type<'a> {
     Message,
     File {
@@ -34,6 +55,8 @@ pub enum GqgError {
     InvalidFileName,
     AuthFailure,
     DecompressFailure,
+    ChecksumMismatch,
+    IoFailure,
 }
 
 #[derive(PartialEq, Debug)]
@@ -42,6 +65,12 @@ pub struct Decoded {
     pub data: DecodedData
 }
 
+#[derive(PartialEq, Debug)]
+pub struct DecodedStream {
+    pub sender: PublicKey,
+    pub file_name: String,
+}
+
 #[derive(PartialEq, Debug)]
 pub enum DecodedData {
     Message {
@@ -90,9 +119,7 @@ pub fn encode(from: &SecretKey, to: &PublicKey, typ: Type, flags: EncodeFlags, d
             ascii.push_str(HEADER_FILE);
         }
     };
-    ascii.push(':');
-    ascii.push_str(&base64::encode(payload));
-    ascii.push_str(FOOTER);
+    ascii.push_str(&encode_armor(&payload));
     return Ok(ascii);
 }
 
@@ -100,6 +127,97 @@ fn remove_whitespace(s: &mut String) {
     s.retain(|c| !c.is_whitespace());
 }
 
+fn wrap_base64(encoded: &str) -> String {
+    let mut wrapped = String::with_capacity(encoded.len() + encoded.len() / ARMOR_LINE_WIDTH + 1);
+    for line in encoded.as_bytes().chunks(ARMOR_LINE_WIDTH) {
+        wrapped.push_str(std::str::from_utf8(line).unwrap());
+        wrapped.push('\n');
+    }
+    wrapped
+}
+
+// Renders `payload` as the wrapped-base64-plus-checksum body shared by every
+// armored type (":\n<64-col base64 lines>=<crc24>]"; the caller prepends its
+// own header, the footer is already included).
+fn encode_armor(payload: &[u8]) -> String {
+    let mut ascii = String::with_capacity(payload.len() * 2);
+    ascii.push(':');
+    ascii.push('\n');
+    ascii.push_str(&wrap_base64(&base64::encode(payload)));
+    ascii.push('=');
+    ascii.push_str(&base64::encode(&crc24(payload).to_be_bytes()[1..]));
+    ascii.push_str(FOOTER);
+    ascii
+}
+
+// Inverse of `encode_armor`, starting from the header onward. Strips
+// whitespace, matches one of `headers` (returning its index), verifies the
+// CRC-24 checksum, and returns the decoded payload bytes.
+fn decode_armor(headers: &[&str], mut payload: String) -> Result<(usize, Vec<u8>), GqgError> {
+    remove_whitespace(&mut payload);
+    let mut payload: &str = &payload;
+    let mut matched = None;
+    for (i, header) in headers.iter().enumerate() {
+        if payload.starts_with(header) {
+            payload = &payload[header.len()..];
+            matched = Some(i);
+            break;
+        }
+    }
+    let matched = matched.ok_or(GqgError::InvalidOuterEncoding)?;
+    if !payload.ends_with(FOOTER) {
+        return Err(GqgError::InvalidOuterEncoding);
+    }
+    // From here on the remaining content is attacker-controlled and not
+    // guaranteed to be ASCII, so slice on bytes rather than `&str` — a
+    // byte offset chosen by length (not a char boundary) would otherwise
+    // let mangled copy/paste panic the process instead of erroring out.
+    let payload = payload.as_bytes();
+    let payload = &payload[..payload.len()-1];
+    if payload.first() != Some(&b':') {
+        return Err(GqgError::InvalidOuterEncoding);
+    }
+    let payload = &payload[1..];
+    // The checksum line is always a literal '=' followed by the base64 of a
+    // fixed 3-byte CRC, i.e. exactly 4 base64 characters (3 bytes divides
+    // evenly into base64, so there is never any '=' padding to confuse with
+    // the checksum marker itself). That makes it safe to split off the last
+    // 5 bytes even after remove_whitespace has erased the line break.
+    if payload.len() < 5 {
+        return Err(GqgError::InvalidOuterEncoding);
+    }
+    let (payload, checksum) = payload.split_at(payload.len() - 5);
+    if checksum.first() != Some(&b'=') {
+        return Err(GqgError::InvalidOuterEncoding);
+    }
+    let checksum = base64::decode(&checksum[1..]).map_err(|_| GqgError::InvalidOuterEncoding)?;
+    if checksum.len() != 3 {
+        return Err(GqgError::InvalidOuterEncoding);
+    }
+    let expected_crc = (checksum[0] as u32) << 16 | (checksum[1] as u32) << 8 | checksum[2] as u32;
+    let payload = base64::decode(payload).map_err(|_| GqgError::InvalidOuterEncoding)?;
+    if crc24(&payload) != expected_crc {
+        return Err(GqgError::ChecksumMismatch);
+    }
+    Ok((matched, payload))
+}
+
+// Classic CRC-24 as used by RFC 4880 ASCII armor.
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = CRC24_INIT;
+    for &byte in data {
+        crc ^= (byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= CRC24_POLY;
+            }
+        }
+        crc &= 0xFFFFFF;
+    }
+    crc
+}
+
 fn validate_file_name(file_name: &str) -> bool {
     if file_name.len() > 32 {
         return false;
@@ -119,30 +237,9 @@ fn validate_file_name(file_name: &str) -> bool {
     return true;
 }
 
-pub fn decode(myself: &SecretKey, mut payload: String) -> Result<Decoded, GqgError> {
-    remove_whitespace(&mut payload);
-    let mut payload: &str = &payload;
-    let is_file;
-    if payload.starts_with(HEADER_MESSAGE) {
-        payload = &payload[HEADER_MESSAGE.len()..];
-        is_file = false;
-    }
-    else if payload.starts_with(HEADER_FILE) {
-        payload = &payload[HEADER_FILE.len()..];
-        is_file = true;
-    }
-    else {
-        return Err(GqgError::InvalidOuterEncoding);
-    }
-    if !payload.ends_with(FOOTER) {
-        return Err(GqgError::InvalidOuterEncoding);
-    }
-    let payload = &payload[..payload.len()-1];
-    if !payload.starts_with(":") {
-        return Err(GqgError::InvalidOuterEncoding);
-    }
-    let payload = &payload[1..];
-    let payload = base64::decode(payload).map_err(|_| GqgError::InvalidOuterEncoding)?;
+pub fn decode(myself: &SecretKey, payload: String) -> Result<Decoded, GqgError> {
+    let (matched, payload) = decode_armor(&[HEADER_MESSAGE, HEADER_FILE], payload)?;
+    let is_file = matched == 1;
     if payload.len() < crypto::box_::PUBLICKEYBYTES {
         return Err(GqgError::InvalidOuterEncoding);
     }
@@ -191,6 +288,224 @@ pub fn decode(myself: &SecretKey, mut payload: String) -> Result<Decoded, GqgErr
     return Ok(Decoded { sender, data });
 }
 
+// Streams `reader` through a secretstream keyed by a fresh random key, which
+// is itself sealed to `to` so only the recipient can recover it. This bounds
+// memory use to a couple of chunk-sized buffers (the current chunk and one
+// read ahead to spot the final one) and authenticates each chunk as it is written,
+// unlike `encode` which seals (and buffers) the whole payload at once.
+pub fn encode_stream<R: Read, W: Write>(from: &SecretKey, to: &PublicKey, file_name: &str, flags: EncodeFlags, mut reader: R, mut writer: W) -> Result<(), GqgError> {
+    if !validate_file_name(file_name) {
+        return Err(GqgError::InvalidFileName);
+    }
+    let stream_key = crypto::secretstream::gen_key();
+    let (mut stream, stream_header) = crypto::secretstream::Stream::init_push(&stream_key)
+        .map_err(|_| GqgError::AuthFailure)?;
+
+    let mut header_plain: Vec<u8> = Vec::new();
+    header_plain.extend_from_slice(file_name.as_bytes());
+    header_plain.push(0);
+    match flags {
+        EncodeFlags::None => header_plain.push(0),
+        EncodeFlags::Compressed => header_plain.push(1),
+    };
+    header_plain.extend_from_slice(&stream_key[..]);
+    header_plain.extend_from_slice(&stream_header[..]);
+
+    let nonce = crypto::box_::gen_nonce();
+    let mut header_payload: Vec<u8> = Vec::new();
+    header_payload.extend(&from.public_key()[..]);
+    header_payload.extend_from_slice(&nonce[..]);
+    header_payload.extend_from_slice(&crypto::box_::seal(&header_plain, &nonce, &to, &from));
+
+    let mut header_ascii = String::with_capacity(0x400);
+    header_ascii.push_str(HEADER_FILE2);
+    header_ascii.push_str(&encode_armor(&header_payload));
+    header_ascii.push('\n');
+    writer.write_all(header_ascii.as_bytes()).map_err(|_| GqgError::IoFailure)?;
+
+    let mut chunk = vec![0u8; STREAM_CHUNK_SIZE];
+    let mut chunk_len = reader.read(&mut chunk).map_err(|_| GqgError::IoFailure)?;
+    loop {
+        let mut lookahead = vec![0u8; STREAM_CHUNK_SIZE];
+        let lookahead_len = reader.read(&mut lookahead).map_err(|_| GqgError::IoFailure)?;
+        let is_final = lookahead_len == 0;
+
+        let plain = match flags {
+            EncodeFlags::None => chunk[..chunk_len].to_vec(),
+            EncodeFlags::Compressed => lz4_compress::compress(&chunk[..chunk_len]),
+        };
+        let tag = if is_final { crypto::secretstream::Tag::Final } else { crypto::secretstream::Tag::Message };
+        let ciphertext = stream.push(&plain, None, tag).map_err(|_| GqgError::AuthFailure)?;
+        writer.write_all(base64::encode(&ciphertext).as_bytes()).map_err(|_| GqgError::IoFailure)?;
+        writer.write_all(b"\n").map_err(|_| GqgError::IoFailure)?;
+
+        if is_final {
+            break;
+        }
+        chunk = lookahead;
+        chunk_len = lookahead_len;
+    }
+    Ok(())
+}
+
+// Inverse of `encode_stream`: reads the armored header line(s) to recover the
+// secretstream key, then pulls and authenticates ciphertext chunks one line
+// at a time, writing plaintext to `writer` as it goes.
+pub fn decode_stream<R: Read, W: Write>(myself: &SecretKey, reader: R, mut writer: W) -> Result<DecodedStream, GqgError> {
+    let mut reader = std::io::BufReader::new(reader);
+
+    let mut header_ascii = String::new();
+    loop {
+        let mut line = String::new();
+        // Read at most one byte past the cap, so a single over-long line is
+        // caught below without being buffered in full.
+        let limit = (MAX_STREAM_HEADER_LEN + 1 - header_ascii.len()) as u64;
+        let read = (&mut reader).take(limit).read_line(&mut line).map_err(|_| GqgError::IoFailure)?;
+        if read == 0 {
+            return Err(GqgError::InvalidOuterEncoding);
+        }
+        header_ascii.push_str(&line);
+        if header_ascii.len() > MAX_STREAM_HEADER_LEN {
+            return Err(GqgError::InvalidOuterEncoding);
+        }
+        if line.trim_end().ends_with(FOOTER) {
+            break;
+        }
+    }
+    let (_, header_payload) = decode_armor(&[HEADER_FILE2], header_ascii)?;
+
+    if header_payload.len() < crypto::box_::PUBLICKEYBYTES {
+        return Err(GqgError::InvalidOuterEncoding);
+    }
+    let sender = PublicKey::from_slice(&header_payload[..crypto::box_::PUBLICKEYBYTES]).unwrap();
+    let header_payload = &header_payload[crypto::box_::PUBLICKEYBYTES..];
+    if header_payload.len() < crypto::box_::NONCEBYTES {
+        return Err(GqgError::InvalidOuterEncoding);
+    }
+    let nonce = Nonce::from_slice(&header_payload[..crypto::box_::NONCEBYTES]).unwrap();
+    let header_payload = &header_payload[crypto::box_::NONCEBYTES..];
+    let header_plain = crypto::box_::open(header_payload, &nonce, &sender, &myself).map_err(|_| GqgError::InvalidOuterEncoding)?;
+
+    let separator = header_plain.iter().position(|x| *x == 0).ok_or(GqgError::InvalidOuterEncoding)?;
+    let file_name = std::str::from_utf8(&header_plain[..separator]).map_err(|_| GqgError::InvalidFileName)?.to_string();
+    let rest = &header_plain[separator+1..];
+    if rest.len() < 1 {
+        return Err(GqgError::InvalidInnerEncoding);
+    }
+    let compressed = match rest[0] {
+        0 => false,
+        1 => true,
+        _ => return Err(GqgError::InvalidInnerEncoding),
+    };
+    let rest = &rest[1..];
+    if rest.len() != crypto::secretstream::KEYBYTES + crypto::secretstream::HEADERBYTES {
+        return Err(GqgError::InvalidOuterEncoding);
+    }
+    let stream_key = crypto::secretstream::Key::from_slice(&rest[..crypto::secretstream::KEYBYTES])
+        .ok_or(GqgError::InvalidOuterEncoding)?;
+    let stream_header = crypto::secretstream::Header::from_slice(&rest[crypto::secretstream::KEYBYTES..])
+        .ok_or(GqgError::InvalidOuterEncoding)?;
+    let mut stream = crypto::secretstream::Stream::init_pull(&stream_header, &stream_key)
+        .map_err(|_| GqgError::AuthFailure)?;
+
+    loop {
+        let mut line = String::new();
+        let read = reader.read_line(&mut line).map_err(|_| GqgError::IoFailure)?;
+        if read == 0 {
+            return Err(GqgError::InvalidInnerEncoding);
+        }
+        remove_whitespace(&mut line);
+        if line.is_empty() {
+            continue;
+        }
+        let ciphertext = base64::decode(&line).map_err(|_| GqgError::InvalidOuterEncoding)?;
+        let (plain, tag) = stream.pull(&ciphertext, None).map_err(|_| GqgError::AuthFailure)?;
+        let plain = if compressed {
+            lz4_compress::decompress(&plain).map_err(|_| GqgError::DecompressFailure)?
+        } else {
+            plain
+        };
+        writer.write_all(&plain).map_err(|_| GqgError::IoFailure)?;
+        match tag {
+            crypto::secretstream::Tag::Final => break,
+            _ => {}
+        }
+    }
+
+    Ok(DecodedStream { sender, file_name })
+}
+
+// The signing keypair is derived from the identity's box secret key rather
+// than stored separately, so existing identities (plain or passphrase
+// protected) gain signing for free with no config file migration.
+fn derive_signing_keypair(box_secret: &SecretKey) -> Result<(crypto::sign::PublicKey, crypto::sign::SecretKey), GqgError> {
+    let mut state = crypto::generichash::State::new(Some(crypto::sign::SEEDBYTES), None)
+        .map_err(|_| GqgError::AuthFailure)?;
+    state.update(&box_secret[..]).map_err(|_| GqgError::AuthFailure)?;
+    let digest = state.finalize().map_err(|_| GqgError::AuthFailure)?;
+    let seed = crypto::sign::Seed::from_slice(digest.as_ref()).ok_or(GqgError::AuthFailure)?;
+    Ok(crypto::sign::keypair_from_seed(&seed))
+}
+
+// Base64 of the Ed25519 public key `sign()` would embed for this identity, so
+// it can be handed to a friend out of band and recorded via
+// `Database::add_friend` — the trust anchor `verify()` checks signatures
+// against, since the blob itself is attacker-controlled and proves nothing
+// about who really owns `sender` on its own.
+pub fn signing_public_key(identity: &database::Identity, passphrase: Option<&str>) -> Result<String, GqgError> {
+    let box_secret = identity.get_private_key(passphrase).map_err(|_| GqgError::AuthFailure)?;
+    let (sign_pk, _) = derive_signing_keypair(&box_secret)?;
+    Ok(base64::encode(&sign_pk[..]))
+}
+
+// Publicly vouches for `data` without encrypting it to anyone: the blob
+// carries the signer's box public id (so `Database::find_friend_by_key` can
+// resolve them) and the Ed25519 public key needed to check the signature.
+pub fn sign(identity: &database::Identity, passphrase: Option<&str>, data: &[u8]) -> Result<String, GqgError> {
+    let box_secret = identity.get_private_key(passphrase).map_err(|_| GqgError::AuthFailure)?;
+    let (sign_pk, sign_sk) = derive_signing_keypair(&box_secret)?;
+    let signature = crypto::sign::sign_detached(data, &sign_sk);
+
+    let mut payload: Vec<u8> = Vec::new();
+    payload.extend_from_slice(&box_secret.public_key()[..]);
+    payload.extend_from_slice(&sign_pk[..]);
+    payload.extend_from_slice(signature.as_ref());
+
+    let mut ascii = String::with_capacity(0x200);
+    ascii.push_str(HEADER_SIG);
+    ascii.push_str(&encode_armor(&payload));
+    Ok(ascii)
+}
+
+// `sign_pk` in the blob is just attacker-controlled bytes on its own — it
+// only proves `sender` once `db` has a friend registered under that box
+// public key whose *stored* `sign_pk` (learned out of band, via
+// `signing_public_key`) matches what's embedded here. Without that match an
+// attacker could pair anyone's real public id with a throwaway keypair of
+// their own and have `verify()` return that victim's identity for data they
+// never signed.
+pub fn verify(db: &database::Database, blob: &str, data: &[u8]) -> Result<PublicKey, GqgError> {
+    let (_, payload) = decode_armor(&[HEADER_SIG], blob.to_string())?;
+    let expected_len = crypto::box_::PUBLICKEYBYTES + crypto::sign::PUBLICKEYBYTES + crypto::sign::SIGNATUREBYTES;
+    if payload.len() != expected_len {
+        return Err(GqgError::InvalidOuterEncoding);
+    }
+    let sender = PublicKey::from_slice(&payload[..crypto::box_::PUBLICKEYBYTES]).unwrap();
+    let payload = &payload[crypto::box_::PUBLICKEYBYTES..];
+    let sign_pk = crypto::sign::PublicKey::from_slice(&payload[..crypto::sign::PUBLICKEYBYTES])
+        .ok_or(GqgError::InvalidOuterEncoding)?;
+    let signature = crypto::sign::Signature::from_bytes(&payload[crypto::sign::PUBLICKEYBYTES..])
+        .map_err(|_| GqgError::InvalidOuterEncoding)?;
+    if !crypto::sign::verify_detached(&signature, data, &sign_pk) {
+        return Err(GqgError::AuthFailure);
+    }
+    let friend = db.find_friend_by_key(&sender).ok_or(GqgError::AuthFailure)?;
+    if friend.get_signing_public_key() != Some(sign_pk) {
+        return Err(GqgError::AuthFailure);
+    }
+    Ok(sender)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -236,6 +551,14 @@ mod tests {
             Err(GqgError::InvalidOuterEncoding));
     }
 
+    #[test]
+    fn test_decode_non_ascii_tail_does_not_panic() {
+        // A multi-byte UTF-8 character landing in the last 5 bytes must be
+        // rejected, not panic the process while slicing by byte length.
+        let (_, to_sk) = crypto::box_::gen_keypair();
+        assert!(decode(&to_sk, format!("{}:aé1234]", HEADER_MESSAGE)).is_err());
+    }
+
     fn msg_of_length(len: usize) -> Vec<u8> {
         let mut v: Vec<u8> = Vec::with_capacity(len);
         for _ in 0..len { v.push(0x41); }
@@ -248,7 +571,10 @@ mod tests {
         let (to_pk, to_sk) = crypto::box_::gen_keypair();
         let msg_data = msg_of_length(0x123);
         let msg = encode(&from_sk, &to_pk, Type::Message, EncodeFlags::Compressed, &msg_data).unwrap();
-        let msg_base64: &str = &msg[HEADER_MESSAGE.len()+1..msg.len()-1];
+        let mut flat_msg = msg.clone();
+        remove_whitespace(&mut flat_msg);
+        let inner: &str = &flat_msg[HEADER_MESSAGE.len()+1..flat_msg.len()-1];
+        let (msg_base64, checksum) = inner.split_at(inner.len() - 5);
         let mut msg_inner = base64::decode(msg_base64).unwrap();
         for i in 0..8*msg_inner.len() {
             msg_inner[i/8] ^= 1 << (i%8);
@@ -256,12 +582,14 @@ mod tests {
             corrupted_msg.push_str(HEADER_MESSAGE);
             corrupted_msg.push(':');
             corrupted_msg.push_str(&base64::encode(&msg_inner));
+            corrupted_msg.push_str(checksum);
             corrupted_msg.push_str(FOOTER);
             // Flipping the upper bit of the public key won't return an error from Curve25519.
             if i != 255 {
                 assert!(
                     decode(&to_sk, corrupted_msg.clone()) == Err(GqgError::AuthFailure) ||
-                    decode(&to_sk, corrupted_msg.clone()) == Err(GqgError::InvalidOuterEncoding)
+                    decode(&to_sk, corrupted_msg.clone()) == Err(GqgError::InvalidOuterEncoding) ||
+                    decode(&to_sk, corrupted_msg.clone()) == Err(GqgError::ChecksumMismatch)
                 );
             }
             msg_inner[i/8] ^= 1 << (i%8);
@@ -270,10 +598,17 @@ mod tests {
         uncorrupted_msg.push_str(HEADER_MESSAGE);
         uncorrupted_msg.push(':');
         uncorrupted_msg.push_str(&base64::encode(&msg_inner));
+        uncorrupted_msg.push_str(checksum);
         uncorrupted_msg.push_str(FOOTER);
         assert!(decode(&to_sk, uncorrupted_msg.clone()).is_ok());
     }
 
+    #[test]
+    fn test_crc24_known_vector() {
+        // "123456789" is the standard check string for CRC-24/OPENPGP, expected 0x21CF02.
+        assert_eq!(crc24(b"123456789"), 0x21CF02);
+    }
+
     #[test]
     fn test_encode_decode_message() {
         let (from_pk, from_sk) = crypto::box_::gen_keypair();
@@ -306,4 +641,80 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sign_verify() {
+        let (pk, sk) = crypto::box_::gen_keypair();
+        let identity = database::Identity {
+            name: "test".to_string(),
+            key: database::IdentityKey::Plain { key: base64::encode(&sk[..]) },
+        };
+        let data = b"hello friends";
+        let blob = sign(&identity, None, data).unwrap();
+
+        let sign_pk = signing_public_key(&identity, None).unwrap();
+        let db = database::Database::new_in_memory(vec![database::Friend {
+            name: "test".to_string(),
+            key: identity.get_public_id().unwrap(),
+            sign_pk,
+        }]);
+        assert_eq!(verify(&db, &blob, data).unwrap(), pk);
+        assert_eq!(verify(&db, &blob, b"tampered").unwrap_err(), GqgError::AuthFailure);
+    }
+
+    #[test]
+    fn test_verify_rejects_unregistered_sender() {
+        // An attacker who only knows a victim's public box id cannot pass
+        // that id off as `sender` using their own throwaway signing keypair:
+        // `verify()` must not trust a binding it never stored.
+        let (victim_pk, _) = crypto::box_::gen_keypair();
+        let (_, attacker_sk) = crypto::box_::gen_keypair();
+        let attacker_identity = database::Identity {
+            name: "attacker".to_string(),
+            key: database::IdentityKey::Plain { key: base64::encode(&attacker_sk[..]) },
+        };
+        let data = b"trust me, I'm the victim";
+        let forged_blob = sign(&attacker_identity, None, data).unwrap();
+        let (_, forged_payload) = decode_armor(&[HEADER_SIG], forged_blob).unwrap();
+        // Splice the victim's public box id in where the signer's own id was.
+        let mut forged_payload = forged_payload;
+        forged_payload[..crypto::box_::PUBLICKEYBYTES].copy_from_slice(&victim_pk[..]);
+        let forged_blob = format!("{}{}", HEADER_SIG, encode_armor(&forged_payload));
+
+        let db = database::Database::new_in_memory(Vec::new());
+        assert_eq!(verify(&db, &forged_blob, data).unwrap_err(), GqgError::AuthFailure);
+    }
+
+    #[test]
+    fn test_encode_decode_stream_roundtrip() {
+        let (from_pk, from_sk) = crypto::box_::gen_keypair();
+        let (to_pk, to_sk) = crypto::box_::gen_keypair();
+
+        for &len in &[0, 1, STREAM_CHUNK_SIZE - 1, STREAM_CHUNK_SIZE, STREAM_CHUNK_SIZE + 1, STREAM_CHUNK_SIZE * 2 + 123] {
+            for flags in [EncodeFlags::None, EncodeFlags::Compressed] {
+                let data = msg_of_length(len);
+                let mut encoded: Vec<u8> = Vec::new();
+                encode_stream(&from_sk, &to_pk, "report.bin", flags, &data[..], &mut encoded).unwrap();
+
+                let mut decoded: Vec<u8> = Vec::new();
+                let result = decode_stream(&to_sk, &encoded[..], &mut decoded).unwrap();
+                assert_eq!(result.sender, from_pk);
+                assert_eq!(result.file_name, "report.bin");
+                assert_eq!(decoded, data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_stream_rejects_unterminated_header() {
+        // A header line with no closing `]` must be rejected once it exceeds
+        // MAX_STREAM_HEADER_LEN rather than buffering the rest of the input
+        // (potentially gigabytes) looking for one.
+        let (_, to_sk) = crypto::box_::gen_keypair();
+        let bogus = format!("{}:{}\n", HEADER_FILE2, "A".repeat(MAX_STREAM_HEADER_LEN * 2));
+        let mut decoded: Vec<u8> = Vec::new();
+        assert_eq!(
+            decode_stream(&to_sk, bogus.as_bytes(), &mut decoded).unwrap_err(),
+            GqgError::InvalidOuterEncoding);
+    }
+
 }